@@ -1,40 +1,135 @@
+use crate::beslink::proto::{Cursor, ProtoRead, ProtoWrite};
+use crate::beslink::throttle::Throttle;
 use crate::beslink::{BESLinkError, BES_SYNC, FLASH_BUFFER_SIZE};
 use serialport::SerialPort;
 use std::convert::TryFrom;
 use std::io::ErrorKind::TimedOut;
-use std::io::{Read, Write};
-use std::time::Duration;
+use std::io::{IoSlice, Read, Write};
+use std::time::Instant;
 use tracing::{debug, error, warn};
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum MessageTypes {
-    Sync = 0x50, // Seems to be used at boot for locking with ROM
-    FlashRead = 0x03,
-    StartProgrammer = 0x53,
-    ProgrammerRunning = 0x54,
-    ProgrammerStart = 0x55,
-    ProgrammerInit = 0x60,
-    FlashCommand = 0x65, // Suspect used to push extra commands to flash controller/chip/die
-    EraseBurnStart = 0x61,
-    FlashBurnData = 0x62,
-}
-impl TryFrom<u8> for MessageTypes {
-    type Error = ();
-
-    fn try_from(v: u8) -> Result<Self, Self::Error> {
-        match v {
-            x if x == MessageTypes::Sync as u8 => Ok(MessageTypes::Sync),
-            x if x == MessageTypes::StartProgrammer as u8 => Ok(MessageTypes::StartProgrammer),
-            x if x == MessageTypes::ProgrammerRunning as u8 => Ok(MessageTypes::ProgrammerRunning),
-            x if x == MessageTypes::ProgrammerInit as u8 => Ok(MessageTypes::ProgrammerInit),
-            x if x == MessageTypes::FlashCommand as u8 => Ok(MessageTypes::FlashCommand),
-            x if x == MessageTypes::EraseBurnStart as u8 => Ok(MessageTypes::EraseBurnStart),
-            x if x == MessageTypes::FlashBurnData as u8 => Ok(MessageTypes::FlashBurnData),
-            x if x == MessageTypes::FlashRead as u8 => Ok(MessageTypes::FlashRead),
-            _ => Err(()),
+/// Default number of resync attempts `read_message` will make before giving up.
+pub const DEFAULT_MAX_RESYNCS: u32 = 3;
+
+/// Consecutive empty/timed-out reads tolerated before a frame-in-progress is
+/// treated as stalled and handed to the resync path.
+const STALL_READS_BEFORE_RESYNC: u32 = 200;
+
+/// Declares a `MessageTypes` opcode together with its total on-wire packet
+/// length (sync + type + payload + checksum) in one spot, generating
+/// `TryFrom<u8>` and the length lookup from the same list so the two can't
+/// drift apart the way the old hand-written `decode_message_length` match
+/// could. A variant whose length depends on a second, `packet_id2` byte
+/// (`FlashCommand`'s sub-command selector) supplies a
+/// `{ pattern => len, ...; default }` table instead of a plain integer.
+///
+/// Also generates a typed, single-opcode wrapper per variant in the
+/// [`frames`] module (`frames::Sync`, `frames::FlashRead`, ...), with
+/// `TryFrom<BesMessage>`/`From<_> for BesMessage` round-tripping keyed off
+/// `MessageTypes` — so a new opcode declared here is immediately usable as
+/// its own type, not just a `BesMessage` with a `type1` to remember to
+/// check. The payload inside each wrapper is still the raw bytes rather than
+/// named fields: most of these opcodes are reverse-engineered guesses (see
+/// the "seems to be"/"suspect" comments below), not a documented schema, so
+/// there's nothing honest to name the fields yet. Parsing/writing that raw
+/// payload goes through `ProtoRead`/`ProtoWrite`.
+macro_rules! bes_messages {
+    ( $( $variant:ident = $opcode:literal => $len:tt ),+ $(,)? ) => {
+        #[derive(Debug, PartialEq, Clone, Copy)]
+        pub enum MessageTypes {
+            $( $variant = $opcode, )+
         }
-    }
+
+        impl TryFrom<u8> for MessageTypes {
+            type Error = BESLinkError;
+
+            fn try_from(v: u8) -> Result<Self, Self::Error> {
+                match v {
+                    $( $opcode => Ok(MessageTypes::$variant), )+
+                    _ => Err(BESLinkError::UnknownMessageType(v)),
+                }
+            }
+        }
+
+        /// Total on-wire packet length for a header starting with
+        /// `packet_id1`/`packet_id2` (the type byte and the first payload byte).
+        pub(crate) fn message_wire_length(
+            packet_id1: u8,
+            packet_id2: u8,
+        ) -> Result<u16, BESLinkError> {
+            match MessageTypes::try_from(packet_id1)? {
+                $( MessageTypes::$variant => Ok(bes_messages!(@len packet_id2, $len)), )+
+            }
+        }
+
+        /// Per-opcode typed wrappers generated by [`bes_messages!`]. Named in
+        /// their own module rather than alongside `MessageTypes` so e.g.
+        /// `frames::Sync` can't collide with `std::marker::Sync`.
+        pub mod frames {
+            use super::{BesMessage, MessageTypes};
+            use crate::beslink::{BESLinkError, BES_SYNC};
+            use std::convert::TryFrom;
+
+            $(
+                #[derive(Debug, PartialEq, Clone)]
+                pub struct $variant {
+                    pub payload: Vec<u8>,
+                }
+
+                impl TryFrom<BesMessage> for $variant {
+                    type Error = BESLinkError;
+
+                    /// Typed parse: succeeds only for a `BesMessage` whose
+                    /// `type1` is `MessageTypes::$variant`.
+                    fn try_from(msg: BesMessage) -> Result<Self, Self::Error> {
+                        if msg.type1 != MessageTypes::$variant {
+                            return Err(BESLinkError::UnexpectedReply {
+                                got: msg.type1,
+                                wanted: MessageTypes::$variant,
+                            });
+                        }
+                        Ok($variant { payload: msg.payload })
+                    }
+                }
+
+                impl From<$variant> for BesMessage {
+                    /// Serialization: rebuilds a checksummed `BesMessage`
+                    /// carrying this opcode, ready for `to_vec`/`send_message`.
+                    fn from(typed: $variant) -> BesMessage {
+                        let mut msg = BesMessage {
+                            sync: BES_SYNC,
+                            type1: MessageTypes::$variant,
+                            payload: typed.payload,
+                            checksum: 0,
+                        };
+                        msg.set_checksum();
+                        msg
+                    }
+                }
+            )+
+        }
+    };
+
+    (@len $packet_id2:ident, $len:literal) => { $len };
+    (@len $packet_id2:ident, { $($sub:pat => $sub_len:literal),+ $(,)? ; $default:literal }) => {
+        match $packet_id2 { $( $sub => $sub_len, )+ _ => $default }
+    };
 }
+
+bes_messages! {
+    // Seems to be used at boot for locking with ROM
+    Sync = 0x50 => 8,
+    FlashRead = 0x03 => 6,
+    StartProgrammer = 0x53 => 6,
+    ProgrammerRunning = 0x54 => 6,
+    ProgrammerStart = 0x55 => 6,
+    ProgrammerInit = 0x60 => 11,
+    // Suspect used to push extra commands to flash controller/chip/die
+    FlashCommand = 0x65 => { 2 => 9, 0x08 => 6 ; 22 },
+    EraseBurnStart = 0x61 => 6,
+    FlashBurnData = 0x62 => 8,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct BesMessage {
     pub sync: u8,
@@ -46,10 +141,10 @@ pub struct BesMessage {
 impl BesMessage {
     pub fn to_vec(&self) -> Vec<u8> {
         let mut result: Vec<u8> = vec![];
-        result.push(self.sync);
-        result.push(self.type1 as u8);
-        result.append(&mut self.payload.clone());
-        result.push(self.checksum);
+        result.write_u8(self.sync);
+        result.write_u8(self.type1 as u8);
+        result.write_bytes(&self.payload);
+        result.write_u8(self.checksum);
         return result;
     }
     pub fn set_checksum(&mut self) {
@@ -59,25 +154,23 @@ impl BesMessage {
     }
 }
 
-impl From<Vec<u8>> for BesMessage {
-    fn from(d: Vec<u8>) -> Self {
-        let mut msg = BesMessage {
-            sync: d[0],
-            type1: MessageTypes::Sync,
-            payload: vec![],
-            checksum: d[d.len() - 1],
-        };
+impl TryFrom<Vec<u8>> for BesMessage {
+    type Error = BESLinkError;
 
-        match d[1].try_into() {
-            Ok(type1) => msg.type1 = type1,
-            Err(_) => {
-                println!("Unknown packet type 0x{:02X}", d[1]);
-            }
-        };
+    fn try_from(d: Vec<u8>) -> Result<Self, Self::Error> {
+        let payload_len = d.len().checked_sub(3).ok_or(BESLinkError::InvalidArgs)?;
+        let mut cursor = Cursor::new(&d);
+        let sync = cursor.read_u8()?;
+        let type1 = MessageTypes::try_from(cursor.read_u8()?)?;
+        let payload = cursor.read_bytes(payload_len)?;
+        let checksum = cursor.read_u8()?;
 
-        msg.payload = d[1..d.len() - 1].to_vec();
-
-        return msg;
+        Ok(BesMessage {
+            sync,
+            type1,
+            payload,
+            checksum,
+        })
     }
 }
 
@@ -95,33 +188,147 @@ pub fn send_message(serial_port: &mut Box<dyn SerialPort>, msg: BesMessage) -> s
         }
     };
 }
+
+/// Like [`send_message`], but writes the sync byte, type byte, borrowed
+/// payload slice, and checksum as separate `IoSlice`s instead of flattening
+/// them into one owned `Vec` first via [`BesMessage::to_vec`]. Meant for the
+/// `FlashBurnData` hot path, where `to_vec`'s `payload.clone()` means copying
+/// every `FLASH_BUFFER_SIZE` block on its way out; `to_vec`/`send_message`
+/// are still there for callers that want the contiguous form.
+pub fn send_message_vectored(
+    serial_port: &mut Box<dyn SerialPort>,
+    msg: &BesMessage,
+) -> std::io::Result<()> {
+    let sync = [msg.sync];
+    let type1 = [msg.type1 as u8];
+    let checksum = [msg.checksum];
+    let len = sync.len() + type1.len() + msg.payload.len() + checksum.len();
+
+    let result = write_all_vectored(
+        serial_port.as_mut(),
+        &mut [&sync[..], &type1[..], &msg.payload[..], &checksum[..]],
+    );
+    match &result {
+        Ok(_) => {
+            debug!("Wrote {} bytes (vectored)", len);
+            let _ = serial_port.flush();
+        }
+        Err(e) => error!("Writing to port raised {:?}", e),
+    }
+    result
+}
+
+/// Writes every piece in `bufs` in order, re-issuing `write_vectored` with
+/// whatever's left after a partial write. `std::io::Write::write_all_vectored`
+/// would do this for us, but it's still nightly-only, so we track progress
+/// ourselves instead of flattening `bufs` into one owned buffer.
+fn write_all_vectored(w: &mut dyn Write, bufs: &mut [&[u8]]) -> std::io::Result<()> {
+    let mut start = 0;
+    while start < bufs.len() {
+        let slices: Vec<IoSlice> = bufs[start..].iter().map(|b| IoSlice::new(b)).collect();
+        let mut written = w.write_vectored(&slices)?;
+        if written == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                "failed to write whole buffer",
+            ));
+        }
+        while written > 0 && start < bufs.len() {
+            let piece = &mut bufs[start];
+            if written >= piece.len() {
+                written -= piece.len();
+                start += 1;
+            } else {
+                *piece = &piece[written..];
+                written = 0;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like [`send_message`], but paces the send through `throttle` and folds
+/// the packet size into its running bytes/sec estimate. Meant for the
+/// `FlashBurnData` hot path, where a fixed `sleep(5ms)` used to be hard-coded
+/// between every packet regardless of what the adapter could actually take.
+/// Sends via [`send_message_vectored`] rather than [`send_message`] so this
+/// hot path never pays for `to_vec`'s `payload.clone()`.
+pub fn send_message_throttled(
+    serial_port: &mut Box<dyn SerialPort>,
+    msg: BesMessage,
+    throttle: &mut Throttle,
+) -> std::io::Result<()> {
+    throttle.wait();
+    let len = 3 + msg.payload.len();
+    send_message_vectored(serial_port, &msg)?;
+    throttle.record(len);
+    Ok(())
+}
+
+/// Sends `request` and waits for a reply of type `expected`, replacing the
+/// "assume the 0x03 code for response" guesswork and the magic `sleep(5ms)`
+/// callers used to thread together by hand. A reply that parses fine but
+/// isn't the type we asked for comes back as `BESLinkError::UnexpectedReply`
+/// instead of being silently treated as the thing we wanted.
+pub fn send_and_await(
+    serial_port: &mut Box<dyn SerialPort>,
+    request: BesMessage,
+    expected: MessageTypes,
+    deadline: Instant,
+    max_resyncs: u32,
+) -> Result<BesMessage, BESLinkError> {
+    send_message(serial_port, request)?;
+
+    let reply = read_message(serial_port, deadline, max_resyncs)?;
+    if reply.type1 != expected {
+        error!("Got {:?} while waiting for {:?}", reply.type1, expected);
+        return Err(BESLinkError::UnexpectedReply {
+            got: reply.type1,
+            wanted: expected,
+        });
+    }
+    Ok(reply)
+}
+
+/// Sends `request`, confirms the `FlashRead` acknowledgement via
+/// [`send_and_await`], then reads `expected_data_len` bytes of trailing flash
+/// data off the wire, all under the one `deadline`.
 pub fn read_message_with_trailing_data(
     serial_port: &mut Box<dyn SerialPort>,
+    request: BesMessage,
     expected_data_len: usize,
+    deadline: Instant,
+    max_resyncs: u32,
+    mut progress: Option<&mut Throttle>,
 ) -> Result<(BesMessage, Vec<u8>), BESLinkError> {
-    //First read the packet; then read the expected_raw_bytes from the uart
-    //TODO for now assuming the 0x03 code for response
-
-    let response = read_message(serial_port)?;
-    if response.type1 != MessageTypes::FlashRead {
-        error!("Bad packet type: {:?}", response.type1);
-        return Err(BESLinkError::InvalidArgs);
-    }
+    let response = send_and_await(
+        serial_port,
+        request,
+        MessageTypes::FlashRead,
+        deadline,
+        max_resyncs,
+    )?;
     let mut packet: Vec<u8> = vec![];
     let mut buffer: [u8; FLASH_BUFFER_SIZE] = [0; FLASH_BUFFER_SIZE];
 
     while packet.len() < expected_data_len {
+        if Instant::now() >= deadline {
+            return Err(BESLinkError::Timeout);
+        }
         match serial_port.read(&mut buffer) {
             Ok(n) => {
                 if n > 0 {
                     packet.extend(&buffer[0..n]);
+                    if let Some(throttle) = progress.as_deref_mut() {
+                        throttle.record(n);
+                    }
                 } else {
                     warn!("Stalled packet");
                 }
             }
             Err(e) => {
                 if e.kind() != TimedOut {
-                    println!("Error reading packet header {:?}", e);
+                    error!("Error reading packet header {:?}", e);
                     return Err(BESLinkError::from(e));
                 }
             }
@@ -129,52 +336,144 @@ pub fn read_message_with_trailing_data(
     }
     return Ok((response, packet));
 }
-pub fn read_message(serial_port: &mut Box<dyn SerialPort>) -> Result<BesMessage, BESLinkError> {
-    //
-    let mut packet: Vec<u8> = vec![];
-    let mut packet_len: usize = 3; //Start expectations at the minimum
-    let mut buffer: [u8; 1] = [0; 1];
 
-    while packet.len() < packet_len {
-        match serial_port.read(&mut buffer) {
-            Ok(n) => {
-                if n == 1 {
-                    // Only grab if actual data
-                    if !(packet.len() == 0 && buffer[0] != BES_SYNC) {
-                        packet.push(buffer[0]);
-                    }
+/// Reads one `BesMessage`, automatically resyncing the link if it gets confused.
+///
+/// A single dropped or spurious byte can desync the frame boundary, which then
+/// fails every subsequent checksum forever unless something re-finds the next
+/// `BES_SYNC`. If the in-progress frame turns out to have a bad checksum, an
+/// unrecognised `type1`, or simply stalls for too long, the leading byte of
+/// whatever's buffered is dropped and scanning resumes for the next candidate
+/// sync byte, up to `max_resyncs` attempts. `deadline` bounds the whole call so
+/// a truly dead link returns `BESLinkError::Timeout` instead of blocking forever.
+pub fn read_message(
+    serial_port: &mut Box<dyn SerialPort>,
+    deadline: Instant,
+    max_resyncs: u32,
+) -> Result<BesMessage, BESLinkError> {
+    let mut buf: Vec<u8> = vec![];
+    let mut resyncs = 0;
+
+    loop {
+        match read_one_frame(serial_port, &mut buf, deadline) {
+            Ok(msg) => return Ok(msg),
+            Err(FrameError::Fatal(e)) => return Err(e),
+            Err(FrameError::Recoverable(e)) => {
+                if resyncs >= max_resyncs {
+                    error!("Giving up after {} resyncs: {:?}", resyncs, e);
+                    return Err(e);
                 }
+                resyncs += 1;
+                warn!("{:?}, resyncing (attempt {}/{})", e, resyncs, max_resyncs);
+                // The byte we trusted as a sync marker was wrong; drop it and
+                // keep scanning whatever we've already buffered for the next
+                // candidate `BES_SYNC` instead of throwing the buffer away.
+                if !buf.is_empty() {
+                    buf.remove(0);
+                }
+            }
+        }
+    }
+}
+
+/// Either an unrecoverable error (stop the whole `read_message` call) or one
+/// that's worth retrying via a resync attempt.
+enum FrameError {
+    Fatal(BESLinkError),
+    Recoverable(BESLinkError),
+}
+
+/// Scans `buf` (topping it up from `serial_port` as needed) for one complete,
+/// checksum- and type-valid frame starting at the next `BES_SYNC` byte.
+fn read_one_frame(
+    serial_port: &mut Box<dyn SerialPort>,
+    buf: &mut Vec<u8>,
+    deadline: Instant,
+) -> Result<BesMessage, FrameError> {
+    let mut stalls = 0u32;
+    let mut byte: [u8; 1] = [0; 1];
+
+    loop {
+        if Instant::now() >= deadline {
+            return Err(FrameError::Fatal(BESLinkError::Timeout));
+        }
+
+        if let Some(result) = try_extract_frame(buf) {
+            return result.map_err(FrameError::Recoverable);
+        }
+
+        match serial_port.read(&mut byte) {
+            Ok(1) => {
+                stalls = 0;
+                buf.push(byte[0]);
             }
+            Ok(_) => stalls += 1,
+            Err(e) if e.kind() == TimedOut => stalls += 1,
             Err(e) => {
-                if e.kind() != TimedOut {
-                    println!("Error reading packet header {:?}", e);
-                    return Err(BESLinkError::from(e));
-                }
+                error!("Error reading packet header {:?}", e);
+                return Err(FrameError::Fatal(BESLinkError::from(e)));
             }
         }
-        if packet.len() == 3 && packet_len == 3 {
-            //Check actual packet length
-            packet_len = decode_message_length(&packet) as usize;
-            debug!("Got packet len lookup {} for {}", packet_len, packet[1])
+
+        if stalls >= STALL_READS_BEFORE_RESYNC {
+            return Err(FrameError::Recoverable(BESLinkError::Stall));
         }
-        //TODO timeout
     }
-    std::thread::sleep(Duration::from_millis(5));
+}
+
+/// Drops leading bytes from `buf` until it's empty or starts with `BES_SYNC`.
+pub(crate) fn drop_until_sync(buf: &mut Vec<u8>) {
+    while !buf.is_empty() && buf[0] != BES_SYNC {
+        buf.remove(0);
+    }
+}
 
-    return match validate_packet_checksum(&packet) {
-        Ok(_) => Ok(BesMessage::from(packet)),
-        Err(e) => Err(e),
+/// Tries to pull one complete frame out of `buf` without touching the wire.
+/// Shared by both the sync and async `read_one_frame`, so the two transports
+/// can't drift apart on this logic the way they did before: `None` means
+/// `buf` doesn't yet hold a full candidate frame (top it up and try again);
+/// `Some(Err(_))` means a full-length candidate was present but failed
+/// checksum/type validation, in which case `buf` is left untouched other
+/// than the leading-garbage drop, so the caller's resync (drop one byte,
+/// rescan) can still find a legitimate frame buried inside it.
+pub(crate) fn try_extract_frame(buf: &mut Vec<u8>) -> Option<Result<BesMessage, BESLinkError>> {
+    drop_until_sync(buf);
+
+    if buf.is_empty() {
+        return None;
+    }
+    let packet_len = (decode_message_length(buf) as usize).max(3);
+    if buf.len() < packet_len {
+        return None;
+    }
+
+    debug!("Got packet len lookup {} for {}", packet_len, buf[1]);
+    if let Err(e) = validate_packet_checksum_slice(&buf[..packet_len]) {
+        return Some(Err(e));
+    }
+    let msg = match BesMessage::try_from(buf[..packet_len].to_vec()) {
+        Ok(msg) => msg,
+        Err(e) => return Some(Err(e)),
     };
+    buf.drain(..packet_len);
+    Some(Ok(msg))
 }
 pub fn validate_packet_checksum(packet: &Vec<u8>) -> Result<(), BESLinkError> {
-    let mut inner_packet = packet.clone();
-    let _ = inner_packet.pop();
-    let checksum = calculate_message_checksum(&inner_packet);
+    validate_packet_checksum_slice(packet)
+}
+
+/// Same check as [`validate_packet_checksum`], but over a borrowed slice so a
+/// candidate frame can be validated before it's drained out of a resync
+/// buffer (see [`try_extract_frame`], which both the sync and the
+/// [`nonblocking`](super::nonblocking) `read_one_frame` call).
+pub(crate) fn validate_packet_checksum_slice(packet: &[u8]) -> Result<(), BESLinkError> {
+    let inner_packet = &packet[..packet.len() - 1];
+    let checksum = calculate_message_checksum_slice(inner_packet);
     if checksum == packet[packet.len() - 1] {
         return Ok(());
     }
     let e = BESLinkError::BadChecksumError {
-        failed_packet: packet.clone(),
+        failed_packet: packet.to_vec(),
         got: packet[packet.len() - 1],
         wanted: checksum,
     };
@@ -182,6 +481,10 @@ pub fn validate_packet_checksum(packet: &Vec<u8>) -> Result<(), BESLinkError> {
     return Err(e);
 }
 pub fn calculate_message_checksum(packet: &Vec<u8>) -> u8 {
+    calculate_message_checksum_slice(packet)
+}
+
+fn calculate_message_checksum_slice(packet: &[u8]) -> u8 {
     let mut sum: u32 = 0;
     for b in packet {
         sum += *b as u32;
@@ -189,47 +492,288 @@ pub fn calculate_message_checksum(packet: &Vec<u8>) -> u8 {
     }
     return (0xFF - sum) as u8;
 }
-fn decode_message_length(packet: &Vec<u8>) -> u16 {
+pub(crate) fn decode_message_length(packet: &Vec<u8>) -> u16 {
     if packet.len() < 3 {
         return 3; // fail safe
     }
-    let packet_id1 = packet[1];
-    let packet_id2 = packet[2];
-
-    return match packet_id1.try_into() {
-        Ok(type1) => match type1 {
-            MessageTypes::Sync => 8,
-            MessageTypes::StartProgrammer => 6,
-            MessageTypes::ProgrammerRunning => 6,
-            MessageTypes::ProgrammerInit => 11,
-            MessageTypes::FlashCommand => {
-                if packet_id2 == 2 {
-                    return 9;
-                } else if packet_id2 == 0x08 {
-                    return 6;
-                }
-                return 22;
-            }
-            MessageTypes::EraseBurnStart => 6,
-            MessageTypes::FlashBurnData => 8,
-            MessageTypes::ProgrammerStart => 6,
-            MessageTypes::FlashRead => {
-                return 6;
-            }
-        },
-        Err(_) => {
-            println!(
-                "Unknown packet len 0x{:02X}/0x{:02X}",
-                packet_id1, packet_id2
-            );
-            return 3;
+    match message_wire_length(packet[1], packet[2]) {
+        Ok(len) => len,
+        Err(e) => {
+            warn!("{:?}", e);
+            3
         }
-    };
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::beslink::message::calculate_message_checksum;
+    use crate::beslink::message::{
+        calculate_message_checksum, drop_until_sync, frames, message_wire_length, read_message,
+        send_and_await, try_extract_frame, write_all_vectored, BesMessage,
+    };
+    use crate::beslink::{BESLinkError, MessageTypes, BES_SYNC};
+    use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+    use std::collections::VecDeque;
+    use std::convert::TryFrom;
+    use std::io::{Read, Write};
+    use std::time::{Duration, Instant};
+
+    /// A `SerialPort` backed by an in-memory byte queue, standing in for real
+    /// hardware so `read_message`'s resync loop can be driven byte-by-byte
+    /// from a fixed script.
+    struct FakeSerialPort {
+        data: VecDeque<u8>,
+    }
+
+    impl FakeSerialPort {
+        fn new(bytes: &[u8]) -> Self {
+            FakeSerialPort {
+                data: bytes.iter().copied().collect(),
+            }
+        }
+    }
+
+    impl Read for FakeSerialPort {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.data.pop_front() {
+                Some(b) => {
+                    buf[0] = b;
+                    Ok(1)
+                }
+                None => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "no data")),
+            }
+        }
+    }
+
+    impl Write for FakeSerialPort {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SerialPort for FakeSerialPort {
+        fn name(&self) -> Option<String> {
+            None
+        }
+        fn baud_rate(&self) -> serialport::Result<u32> {
+            Ok(115200)
+        }
+        fn data_bits(&self) -> serialport::Result<DataBits> {
+            Ok(DataBits::Eight)
+        }
+        fn flow_control(&self) -> serialport::Result<FlowControl> {
+            Ok(FlowControl::None)
+        }
+        fn parity(&self) -> serialport::Result<Parity> {
+            Ok(Parity::None)
+        }
+        fn stop_bits(&self) -> serialport::Result<StopBits> {
+            Ok(StopBits::One)
+        }
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(50)
+        }
+        fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_timeout(&mut self, _: Duration) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+        fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+        fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+            Ok(false)
+        }
+        fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+            Ok(false)
+        }
+        fn bytes_to_read(&self) -> serialport::Result<u32> {
+            Ok(self.data.len() as u32)
+        }
+        fn bytes_to_write(&self) -> serialport::Result<u32> {
+            Ok(0)
+        }
+        fn clear(&self, _: ClearBuffer) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+            Err(serialport::Error::new(
+                serialport::ErrorKind::Unknown,
+                "FakeSerialPort doesn't support try_clone",
+            ))
+        }
+        fn set_break(&self) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn clear_break(&self) -> serialport::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_try_extract_frame_preserves_a_valid_frame_behind_a_corrupted_one() {
+        // Pure, I/O-free version of the scenario below: both the sync and
+        // async `read_one_frame` call `try_extract_frame`, so this one test
+        // guards the validate-before-drain guarantee for both transports.
+        let corrupted = [0xBE, 0x60, 0x00, 0x01, 0x00, 0xED];
+        let valid = [0xBE, 0x53, 0x00, 0x01, 0x00, 0xED];
+        let mut buf = vec![];
+        buf.extend_from_slice(&corrupted);
+        buf.extend_from_slice(&valid);
+
+        // First call: the corrupted candidate fails checksum validation, and
+        // `buf` is left untouched.
+        let first = try_extract_frame(&mut buf);
+        assert!(matches!(first, Some(Err(BESLinkError::BadChecksumError { .. }))));
+        assert_eq!(buf.len(), 12);
+
+        // The caller's resync then drops that one bad byte and tries again;
+        // this time the valid frame is found intact.
+        buf.remove(0);
+        let second = try_extract_frame(&mut buf).expect("valid frame should be found");
+        let msg = second.expect("valid frame should parse");
+        assert_eq!(msg.type1, MessageTypes::StartProgrammer);
+        assert_eq!(msg.payload, vec![0x00, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_read_message_resyncs_past_corrupted_frame_without_losing_the_next_one() {
+        // A `StartProgrammer` frame (`message_wire_length(0x53, _) == 6`) whose
+        // type byte got bit-flipped to `0x60` (`ProgrammerInit`, a fixed
+        // length of 11). The presumed length now overshoots the 6 real bytes
+        // that actually make up this garbage frame, immediately followed by a
+        // genuine, correctly-checksummed `StartProgrammer` frame. If resync
+        // ever drains the mis-sized chunk before validating it, it eats into
+        // (or past) the real frame's sync byte and never recovers it.
+        let corrupted = [0xBE, 0x60, 0x00, 0x01, 0x00, 0xED];
+        let valid = [0xBE, 0x53, 0x00, 0x01, 0x00, 0xED];
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&corrupted);
+        bytes.extend_from_slice(&valid);
+
+        let mut port: Box<dyn SerialPort> = Box::new(FakeSerialPort::new(&bytes));
+        let deadline = Instant::now() + Duration::from_millis(500);
+
+        let msg = read_message(&mut port, deadline, 2).expect("should resync onto the valid frame");
+        assert_eq!(msg.type1, MessageTypes::StartProgrammer);
+        assert_eq!(msg.payload, vec![0x00, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_send_and_await_rejects_unexpected_reply_type() {
+        // The port replies with a well-formed `StartProgrammer` frame while
+        // the caller is waiting on `ProgrammerRunning`; that must surface as
+        // a typed mismatch rather than being accepted as the expected reply.
+        let reply = [0xBE, 0x53, 0x00, 0x01, 0x00, 0xED];
+        let mut port: Box<dyn SerialPort> = Box::new(FakeSerialPort::new(&reply));
+        let deadline = Instant::now() + Duration::from_millis(500);
+        let request = BesMessage {
+            sync: BES_SYNC,
+            type1: MessageTypes::StartProgrammer,
+            payload: vec![0x00, 0x01, 0x00],
+            checksum: 0xED,
+        };
+
+        let err = send_and_await(
+            &mut port,
+            request,
+            MessageTypes::ProgrammerRunning,
+            deadline,
+            0,
+        )
+        .expect_err("reply type mismatch should be rejected");
+        assert!(matches!(
+            err,
+            BESLinkError::UnexpectedReply {
+                got: MessageTypes::StartProgrammer,
+                wanted: MessageTypes::ProgrammerRunning
+            }
+        ));
+    }
+
+    #[test]
+    fn test_write_all_vectored_concatenates_pieces_in_order() {
+        let mut out: Vec<u8> = vec![];
+        write_all_vectored(&mut out, &mut [&[0xBE], &[0x50], &[0x01, 0x02], &[0xED]]).unwrap();
+        assert_eq!(out, vec![0xBE, 0x50, 0x01, 0x02, 0xED]);
+    }
+
+    #[test]
+    fn test_message_wire_length_branches_on_flash_command_sub_type() {
+        assert_eq!(message_wire_length(0x65, 0x02).unwrap(), 9);
+        assert_eq!(message_wire_length(0x65, 0x08).unwrap(), 6);
+        assert_eq!(message_wire_length(0x65, 0x01).unwrap(), 22);
+    }
+
+    #[test]
+    fn test_message_wire_length_rejects_unknown_type() {
+        assert!(message_wire_length(0xFF, 0x00).is_err());
+    }
+
+    #[test]
+    fn test_frame_round_trips_through_bes_message() {
+        let typed = frames::StartProgrammer {
+            payload: vec![0x00, 0x01, 0x00],
+        };
+
+        let msg: BesMessage = typed.clone().into();
+        assert_eq!(msg.type1, MessageTypes::StartProgrammer);
+        assert_eq!(msg.payload, typed.payload);
+        assert_eq!(msg.to_vec(), vec![0xBE, 0x53, 0x00, 0x01, 0x00, 0xED]);
+
+        let parsed = frames::StartProgrammer::try_from(msg).unwrap();
+        assert_eq!(parsed, typed);
+    }
+
+    #[test]
+    fn test_frame_try_from_rejects_the_wrong_opcode() {
+        let msg = BesMessage {
+            sync: BES_SYNC,
+            type1: MessageTypes::FlashRead,
+            payload: vec![],
+            checksum: 0,
+        };
+        assert!(frames::StartProgrammer::try_from(msg).is_err());
+    }
+
+    #[test]
+    fn test_drop_until_sync_strips_leading_garbage() {
+        let mut buf = vec![0x00, 0x11, BES_SYNC, 0x50, 0x00];
+        drop_until_sync(&mut buf);
+        assert_eq!(buf, vec![BES_SYNC, 0x50, 0x00]);
+    }
+
+    #[test]
+    fn test_drop_until_sync_empties_buffer_with_no_sync() {
+        let mut buf = vec![0x00, 0x11, 0x22];
+        drop_until_sync(&mut buf);
+        assert!(buf.is_empty());
+    }
 
     #[test]
     fn test_calculate_packet_checksum() {
@@ -0,0 +1,44 @@
+//! Protocol support for talking to the BES bootloader/ROM loader over a serial link.
+
+pub mod message;
+pub mod proto;
+pub mod throttle;
+
+#[cfg(feature = "async")]
+pub mod nonblocking;
+
+pub use message::{BesMessage, MessageTypes};
+pub use throttle::{Throttle, TransferProgress};
+
+use thiserror::Error;
+
+/// Leading sync byte that starts every `BesMessage` on the wire.
+pub const BES_SYNC: u8 = 0xBE;
+
+/// Size of the scratch buffer used when streaming trailing flash data off the wire.
+pub const FLASH_BUFFER_SIZE: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum BESLinkError {
+    #[error("bad checksum on packet {failed_packet:?}: got 0x{got:02X}, wanted 0x{wanted:02X}")]
+    BadChecksumError {
+        failed_packet: Vec<u8>,
+        got: u8,
+        wanted: u8,
+    },
+    #[error("invalid arguments for this operation")]
+    InvalidArgs,
+    #[error("timed out waiting for a reply")]
+    Timeout,
+    #[error("link stalled: no data for too long")]
+    Stall,
+    #[error("unknown packet type 0x{0:02X}")]
+    UnknownMessageType(u8),
+    #[error("unexpected reply: got {got:?}, wanted {wanted:?}")]
+    UnexpectedReply {
+        got: MessageTypes,
+        wanted: MessageTypes,
+    },
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
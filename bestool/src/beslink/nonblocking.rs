@@ -0,0 +1,304 @@
+//! Async counterpart to [`crate::beslink::message`], built on `tokio-serial`.
+//!
+//! The blocking `read`/`write` loops in [`message`](super::message) stall
+//! the calling thread, which is fine for
+//! a one-shot CLI invocation but awkward for a flasher that wants to drive
+//! several pending reads, apply `tokio::time::timeout` cleanly, and report
+//! progress without spinning up dedicated threads. This module provides
+//! async `send_message`, `send_and_await`, `read_message` and
+//! `read_message_with_trailing_data` on top of `tokio-serial`/`futures` so
+//! callers can pick whichever fits their runtime. It takes `Duration`
+//! timeouts rather than `Instant` deadlines, and has no counterpart to
+//! `send_message_vectored`, `send_message_throttled` or the `Throttle`
+//! progress argument. Both paths share the same
+//! `BesMessage`/`MessageTypes`/checksum core; a frame decoded on one side is
+//! identical to one decoded on the other.
+
+use super::message::try_extract_frame;
+use crate::beslink::{BESLinkError, BesMessage, MessageTypes, FLASH_BUFFER_SIZE};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+use tokio_serial::SerialStream;
+use tracing::{debug, error, warn};
+
+/// Consecutive empty reads tolerated before a frame-in-progress is treated as
+/// stalled; mirrors `message::STALL_READS_BEFORE_RESYNC`.
+const STALL_READS_BEFORE_RESYNC: u32 = 200;
+
+pub async fn send_message(
+    serial_port: &mut SerialStream,
+    msg: BesMessage,
+) -> std::io::Result<()> {
+    let packet = msg.to_vec();
+    match serial_port.write_all(packet.as_slice()).await {
+        Ok(_) => {
+            debug!("Wrote {} bytes", packet.len());
+            let _ = serial_port.flush().await;
+            Ok(())
+        }
+        Err(e) => {
+            error!("Writing to port raised {:?}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Async counterpart to
+/// [`message::send_and_await`](super::message::send_and_await). Unlike the
+/// sync side (which threads an absolute `Instant` deadline through), this
+/// takes a `Duration` and re-arms a fresh `tokio::time::timeout` (inside
+/// [`read_message`]) around reading the reply; the send itself is not under
+/// that timeout. Callers chaining more work afterwards (like
+/// [`read_message_with_trailing_data`]) should compute a remaining duration
+/// from their own deadline rather than passing the same `Duration` through
+/// twice.
+pub async fn send_and_await(
+    serial_port: &mut SerialStream,
+    request: BesMessage,
+    expected: MessageTypes,
+    overall_timeout: Duration,
+    max_resyncs: u32,
+) -> Result<BesMessage, BESLinkError> {
+    send_message(serial_port, request).await?;
+
+    let reply = read_message(serial_port, overall_timeout, max_resyncs).await?;
+    if reply.type1 != expected {
+        error!("Got {:?} while waiting for {:?}", reply.type1, expected);
+        return Err(BESLinkError::UnexpectedReply {
+            got: reply.type1,
+            wanted: expected,
+        });
+    }
+    Ok(reply)
+}
+
+/// Async counterpart to
+/// [`message::read_message_with_trailing_data`](super::message::read_message_with_trailing_data).
+///
+/// `overall_timeout` bounds the *whole* call, the same way `deadline` does on
+/// the sync side: it's converted to a deadline up front, `send_and_await`
+/// gets whatever's left of it, and the trailing-data loop below is bounded by
+/// what's left after that, instead of each phase re-arming the full
+/// `overall_timeout` independently.
+pub async fn read_message_with_trailing_data(
+    serial_port: &mut SerialStream,
+    request: BesMessage,
+    expected_data_len: usize,
+    overall_timeout: Duration,
+    max_resyncs: u32,
+) -> Result<(BesMessage, Vec<u8>), BESLinkError> {
+    let deadline = tokio::time::Instant::now() + overall_timeout;
+
+    let response = send_and_await(
+        serial_port,
+        request,
+        MessageTypes::FlashRead,
+        remaining(deadline)?,
+        max_resyncs,
+    )
+    .await?;
+
+    let mut packet: Vec<u8> = vec![];
+    let mut buffer: [u8; FLASH_BUFFER_SIZE] = [0; FLASH_BUFFER_SIZE];
+
+    let result: Result<(), BESLinkError> = timeout(remaining(deadline)?, async {
+        while packet.len() < expected_data_len {
+            match serial_port.read(&mut buffer).await {
+                Ok(n) if n > 0 => packet.extend(&buffer[0..n]),
+                Ok(_) => warn!("Stalled packet"),
+                Err(e) => {
+                    error!("Error reading packet header {:?}", e);
+                    return Err(BESLinkError::from(e));
+                }
+            }
+        }
+        Ok(())
+    })
+    .await
+    .unwrap_or(Err(BESLinkError::Timeout));
+    result?;
+
+    Ok((response, packet))
+}
+
+/// Time left until `deadline`, or `BESLinkError::Timeout` if it's already passed.
+fn remaining(deadline: tokio::time::Instant) -> Result<Duration, BESLinkError> {
+    deadline
+        .checked_duration_since(tokio::time::Instant::now())
+        .ok_or(BESLinkError::Timeout)
+}
+
+/// Async counterpart to [`message::read_message`](super::message::read_message).
+///
+/// Behaves the same: a bad checksum, an unknown `type1`, or a stalled frame
+/// triggers a resync (drop the leading byte, rescan for the next `BES_SYNC`)
+/// up to `max_resyncs` times, and `overall_timeout` bounds the whole call.
+pub async fn read_message(
+    serial_port: &mut SerialStream,
+    overall_timeout: Duration,
+    max_resyncs: u32,
+) -> Result<BesMessage, BESLinkError> {
+    let mut buf: Vec<u8> = vec![];
+    let mut resyncs = 0;
+
+    timeout(overall_timeout, async {
+        loop {
+            match read_one_frame(serial_port, &mut buf).await {
+                Ok(msg) => return Ok(msg),
+                Err(FrameError::Recoverable(e)) => {
+                    if resyncs >= max_resyncs {
+                        error!("Giving up after {} resyncs: {:?}", resyncs, e);
+                        return Err(e);
+                    }
+                    resyncs += 1;
+                    warn!("{:?}, resyncing (attempt {}/{})", e, resyncs, max_resyncs);
+                    if !buf.is_empty() {
+                        buf.remove(0);
+                    }
+                }
+                Err(FrameError::Fatal(e)) => return Err(e),
+            }
+        }
+    })
+    .await
+    .unwrap_or(Err(BESLinkError::Timeout))
+}
+
+enum FrameError {
+    Fatal(BESLinkError),
+    Recoverable(BESLinkError),
+}
+
+async fn read_one_frame(
+    serial_port: &mut SerialStream,
+    buf: &mut Vec<u8>,
+) -> Result<BesMessage, FrameError> {
+    let mut stalls = 0u32;
+    let mut byte: [u8; 1] = [0; 1];
+
+    loop {
+        if let Some(result) = try_extract_frame(buf) {
+            return result.map_err(FrameError::Recoverable);
+        }
+
+        match timeout(Duration::from_millis(50), serial_port.read(&mut byte)).await {
+            Ok(Ok(1)) => {
+                stalls = 0;
+                buf.push(byte[0]);
+            }
+            Ok(Ok(_)) => stalls += 1,
+            Ok(Err(e)) => {
+                error!("Error reading packet header {:?}", e);
+                return Err(FrameError::Fatal(BESLinkError::from(e)));
+            }
+            Err(_elapsed) => stalls += 1,
+        }
+
+        if stalls >= STALL_READS_BEFORE_RESYNC {
+            return Err(FrameError::Recoverable(BESLinkError::Stall));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_message, read_message_with_trailing_data, send_and_await};
+    use crate::beslink::{BESLinkError, BesMessage, MessageTypes, BES_SYNC};
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio_serial::SerialStream;
+
+    /// Bytes for one correctly-checksummed `FlashRead` ack frame
+    /// (`message_wire_length(0x03, _) == 6`, payload `[0x00, 0x00, 0x00]`).
+    const FLASH_READ_ACK: [u8; 6] = [0xBE, 0x03, 0x00, 0x00, 0x00, 0x3E];
+
+    #[tokio::test]
+    async fn test_read_message_resyncs_past_corrupted_frame_without_losing_the_next_one() {
+        // Same corruption scenario as message.rs's resync test: a
+        // `StartProgrammer` frame whose type byte got bit-flipped to
+        // `0x60` (`ProgrammerInit`, a longer fixed length), immediately
+        // followed by a genuine `StartProgrammer` frame.
+        let corrupted = [0xBE, 0x60, 0x00, 0x01, 0x00, 0xED];
+        let valid = [0xBE, 0x53, 0x00, 0x01, 0x00, 0xED];
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&corrupted);
+        bytes.extend_from_slice(&valid);
+
+        let (mut here, mut peer) = SerialStream::pair().expect("failed to open a pty pair");
+        peer.write_all(&bytes).await.unwrap();
+
+        let msg = read_message(&mut here, Duration::from_millis(500), 2)
+            .await
+            .expect("should resync onto the valid frame");
+        assert_eq!(msg.type1, MessageTypes::StartProgrammer);
+        assert_eq!(msg.payload, vec![0x00, 0x01, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn test_read_message_times_out_when_the_link_is_silent() {
+        let (mut here, _peer) = SerialStream::pair().expect("failed to open a pty pair");
+
+        let err = read_message(&mut here, Duration::from_millis(100), 0)
+            .await
+            .expect_err("a silent link should time out");
+        assert!(matches!(err, BESLinkError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_send_and_await_rejects_unexpected_reply_type() {
+        let (mut here, mut peer) = SerialStream::pair().expect("failed to open a pty pair");
+        peer.write_all(&FLASH_READ_ACK).await.unwrap();
+
+        let request = BesMessage {
+            sync: BES_SYNC,
+            type1: MessageTypes::StartProgrammer,
+            payload: vec![0x00, 0x01, 0x00],
+            checksum: 0xED,
+        };
+        let err = send_and_await(
+            &mut here,
+            request,
+            MessageTypes::ProgrammerRunning,
+            Duration::from_millis(500),
+            0,
+        )
+        .await
+        .expect_err("reply type mismatch should be rejected");
+        assert!(matches!(
+            err,
+            BESLinkError::UnexpectedReply {
+                got: MessageTypes::FlashRead,
+                wanted: MessageTypes::ProgrammerRunning
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_message_with_trailing_data_reads_the_ack_then_the_payload() {
+        let (mut here, mut peer) = SerialStream::pair().expect("failed to open a pty pair");
+        let trailing = [0xAA, 0xBB, 0xCC, 0xDD];
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&FLASH_READ_ACK);
+        bytes.extend_from_slice(&trailing);
+        peer.write_all(&bytes).await.unwrap();
+
+        let request = BesMessage {
+            sync: BES_SYNC,
+            type1: MessageTypes::FlashRead,
+            payload: vec![0x00, 0x00, 0x00],
+            checksum: 0x3E,
+        };
+        let (response, packet) = read_message_with_trailing_data(
+            &mut here,
+            request,
+            trailing.len(),
+            Duration::from_millis(500),
+            2,
+        )
+        .await
+        .expect("ack plus trailing data should be read back");
+        assert_eq!(response.type1, MessageTypes::FlashRead);
+        assert_eq!(packet, trailing);
+    }
+}
@@ -0,0 +1,107 @@
+//! A small `ProtoRead`/`ProtoWrite` cursor pair, in the spirit of ARTIQ's
+//! `libio`, so `BesMessage` parsing and serialization (see
+//! [`message`](super::message)) have one place to pull fixed-width fields off
+//! the wire instead of every call site hand-indexing a `Vec<u8>`.
+
+use crate::beslink::BESLinkError;
+
+/// Reads fixed-width fields off the front of a byte slice, consuming as it goes.
+pub struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+pub trait ProtoRead<'a> {
+    fn read_u8(&mut self) -> Result<u8, BESLinkError>;
+    fn read_u16_be(&mut self) -> Result<u16, BESLinkError>;
+    fn read_u32_be(&mut self) -> Result<u32, BESLinkError>;
+    fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>, BESLinkError>;
+    /// Everything not yet consumed.
+    fn remaining(&self) -> &'a [u8];
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Cursor { buf, pos: 0 }
+    }
+}
+
+impl<'a> ProtoRead<'a> for Cursor<'a> {
+    fn read_u8(&mut self) -> Result<u8, BESLinkError> {
+        let b = *self.buf.get(self.pos).ok_or(BESLinkError::InvalidArgs)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn read_u16_be(&mut self) -> Result<u16, BESLinkError> {
+        let hi = self.read_u8()? as u16;
+        let lo = self.read_u8()? as u16;
+        Ok((hi << 8) | lo)
+    }
+
+    fn read_u32_be(&mut self) -> Result<u32, BESLinkError> {
+        let hi = self.read_u16_be()? as u32;
+        let lo = self.read_u16_be()? as u32;
+        Ok((hi << 16) | lo)
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>, BESLinkError> {
+        let end = self.pos.checked_add(n).ok_or(BESLinkError::InvalidArgs)?;
+        let slice = self
+            .buf
+            .get(self.pos..end)
+            .ok_or(BESLinkError::InvalidArgs)?;
+        self.pos = end;
+        Ok(slice.to_vec())
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+}
+
+/// Appends fixed-width fields to a growing byte buffer.
+pub trait ProtoWrite {
+    fn write_u8(&mut self, v: u8);
+    fn write_u16_be(&mut self, v: u16);
+    fn write_u32_be(&mut self, v: u32);
+    fn write_bytes(&mut self, v: &[u8]);
+}
+
+impl ProtoWrite for Vec<u8> {
+    fn write_u8(&mut self, v: u8) {
+        self.push(v);
+    }
+
+    fn write_u16_be(&mut self, v: u16) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn write_u32_be(&mut self, v: u32) {
+        self.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn write_bytes(&mut self, v: &[u8]) {
+        self.extend_from_slice(v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_reads_big_endian() {
+        let buf = [0x01, 0x02, 0x03, 0x04, 0xAA, 0xBB];
+        let mut cursor = Cursor::new(&buf);
+        assert_eq!(cursor.read_u16_be().unwrap(), 0x0102);
+        assert_eq!(cursor.read_u32_be().unwrap(), 0x0304_u32 << 16 | 0xAABB);
+    }
+
+    #[test]
+    fn test_cursor_read_bytes_errors_past_end() {
+        let buf = [0x01, 0x02];
+        let mut cursor = Cursor::new(&buf);
+        assert!(cursor.read_bytes(3).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! Inter-packet pacing and throughput reporting for the flash burn/read hot
+//! path.
+//!
+//! `FlashBurnData` packets used to be paced with a hard-coded
+//! `thread::sleep(5ms)` between every one, with no way to tune it for a
+//! flaky adapter and no feedback on how fast (or slowly) a large
+//! `read_message_with_trailing_data` transfer is actually going. `Throttle`
+//! replaces that fixed sleep with a caller-supplied minimum delay and keeps a
+//! running bytes/sec estimate that's reported through an optional callback.
+
+use std::time::{Duration, Instant};
+
+/// A snapshot of transfer progress, handed to a [`Throttle`]'s progress
+/// callback every time more bytes are recorded.
+#[derive(Debug, Clone, Copy)]
+pub struct TransferProgress {
+    pub bytes: u64,
+    pub elapsed: Duration,
+    pub bytes_per_sec: f64,
+}
+
+/// Paces `send_message_throttled` sends and tracks a running bytes/sec
+/// estimate; `read_message_with_trailing_data` only feeds the estimate and is
+/// not paced.
+pub struct Throttle {
+    min_delay: Duration,
+    last_send: Option<Instant>,
+    started: Instant,
+    bytes_moved: u64,
+    on_progress: Option<Box<dyn FnMut(TransferProgress)>>,
+}
+
+impl Throttle {
+    /// `min_delay` is the minimum time to leave between successive paced
+    /// sends; `Duration::ZERO` disables pacing and only tracks throughput.
+    pub fn new(min_delay: Duration) -> Self {
+        Throttle {
+            min_delay,
+            last_send: None,
+            started: Instant::now(),
+            bytes_moved: 0,
+            on_progress: None,
+        }
+    }
+
+    pub fn with_progress_callback(mut self, cb: impl FnMut(TransferProgress) + 'static) -> Self {
+        self.on_progress = Some(Box::new(cb));
+        self
+    }
+
+    /// Wraps an `indicatif` bar so every recorded byte advances it and its
+    /// message is updated with the current rate.
+    #[cfg(feature = "progress")]
+    pub fn with_indicatif_bar(self, bar: indicatif::ProgressBar) -> Self {
+        self.with_progress_callback(move |progress| {
+            bar.set_position(progress.bytes);
+            bar.set_message(format!("{:.1} KiB/s", progress.bytes_per_sec / 1024.0));
+        })
+    }
+
+    /// Blocks, if necessary, so at least `min_delay` has elapsed since the
+    /// last call to `wait`.
+    pub fn wait(&mut self) {
+        if let Some(last) = self.last_send {
+            let elapsed = last.elapsed();
+            if elapsed < self.min_delay {
+                std::thread::sleep(self.min_delay - elapsed);
+            }
+        }
+        self.last_send = Some(Instant::now());
+    }
+
+    /// Records `n` more bytes moved and reports progress if a callback was registered.
+    pub fn record(&mut self, n: usize) {
+        self.bytes_moved += n as u64;
+        if let Some(cb) = self.on_progress.as_mut() {
+            let elapsed = self.started.elapsed();
+            let bytes_per_sec = if elapsed.as_secs_f64() > 0.0 {
+                self.bytes_moved as f64 / elapsed.as_secs_f64()
+            } else {
+                0.0
+            };
+            cb(TransferProgress {
+                bytes: self.bytes_moved,
+                elapsed,
+                bytes_per_sec,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_reports_cumulative_bytes() {
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let seen_cb = seen.clone();
+        let mut throttle = Throttle::new(Duration::ZERO)
+            .with_progress_callback(move |p| seen_cb.borrow_mut().push(p.bytes));
+
+        throttle.record(10);
+        throttle.record(5);
+
+        assert_eq!(*seen.borrow(), vec![10, 15]);
+    }
+}